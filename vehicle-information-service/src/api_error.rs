@@ -5,23 +5,41 @@
 //! Includes error that are specified in the VIS specifcation [errors](https://w3c.github.io/automotive/vehicle_data/vehicle_information_service.html#errors).
 //!
 use http::status::StatusCode;
+use std::borrow::Cow;
+use std::error::Error;
 use std::fmt;
 use std::io;
 
 use crate::api_type::{ReqID, SubscriptionID};
 use crate::unix_timestamp_ms;
 
+///
+/// Link to the VIS specification error table, surfaced on every error so
+/// clients can look up the meaning of a given `errno`.
+///
+pub const ERROR_INFO_URL: &str =
+    "https://w3c.github.io/automotive/vehicle_data/vehicle_information_service.html#errors";
+
 ///
 /// If there is an error with any of the client’s requests,
 /// the server responds with an error number, reason and message.
 /// [Errors Doc](https://w3c.github.io/automotive/vehicle_data/vehicle_information_service.html#errors)
 ///
-#[derive(PartialEq, Eq, Debug, Serialize, Copy, Clone)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ActionError {
     ///
     /// HTTP Status Code Number.
     ///
     number: u16,
+    ///
+    /// Stable machine-readable error number, distinct from the HTTP status
+    /// `number`. Several conditions may share one HTTP status (e.g. all the
+    /// `UNAUTHORIZED_*` errors are 401), so clients should branch on `errno`,
+    /// which is assigned per `KnownError` and never changes across spec
+    /// revisions.
+    ///
+    errno: u32,
     // Pre-defined string value that can be used to distinguish between errors that have the same code.
     /// e.g. user_token_expired, user_token_invalid
     ///
@@ -30,20 +48,146 @@ pub struct ActionError {
     /// Message text describing the cause in more detail.
     /// e.g. User token has expired.
     ///
-    pub message: &'static str,
+    /// A [`Cow`] so the `KnownError` constants stay zero-allocation while
+    /// constructors can attach runtime context such as the offending VSS
+    /// path or a wrapped IO error.
+    ///
+    pub message: Cow<'static, str>,
+    ///
+    /// Link to the specification error table, see [`ERROR_INFO_URL`].
+    ///
+    info: &'static str,
+    ///
+    /// Server-provided backoff hint in milliseconds. Populated for
+    /// throttling and upstream-failure statuses (`429`, `503`, `504`) so
+    /// subscribers can back off instead of reconnecting immediately;
+    /// omitted from the JSON for every other error unless an explicit hint
+    /// is given through [`ActionError::throttled`].
+    ///
+    #[serde(rename = "retryAfterMs", skip_serializing_if = "Option::is_none")]
+    retry_after_ms: Option<u64>,
+    ///
+    /// The underlying cause, preserved for `std::error::Error::source` and
+    /// log diagnostics (e.g. the wrapped `io::Error`). Never serialized onto
+    /// the wire and ignored for equality, since two errors are equal when
+    /// their client-visible fields match.
+    ///
+    #[serde(skip)]
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl PartialEq for ActionError {
+    fn eq(&self, other: &Self) -> bool {
+        self.number == other.number
+            && self.errno == other.errno
+            && self.reason == other.reason
+            && self.message == other.message
+            && self.info == other.info
+            && self.retry_after_ms == other.retry_after_ms
+    }
 }
 
-unsafe impl Send for ActionError {}
-unsafe impl Sync for ActionError {}
+impl Eq for ActionError {}
+
+impl Clone for ActionError {
+    fn clone(&self) -> Self {
+        // `Box<dyn Error>` is not `Clone`; the cause is diagnostic only, so a
+        // clone keeps the wire fields and drops the source.
+        Self {
+            number: self.number,
+            errno: self.errno,
+            reason: self.reason,
+            message: self.message.clone(),
+            info: self.info,
+            retry_after_ms: self.retry_after_ms,
+            source: None,
+        }
+    }
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.reason, self.errno, self.message)
+    }
+}
+
+impl Error for ActionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|cause| cause.as_ref() as &(dyn Error + 'static))
+    }
+}
+
+///
+/// The `errno` used for an error that is not one of the known constants.
+/// Derives a stable `<status><seq>` code with sequence `00`, matching the
+/// scheme the named constants use, so `errno` never collides with a bare
+/// HTTP status number.
+///
+fn status_errno(status: StatusCode) -> u32 {
+    u32::from(status.as_u16()) * 100
+}
+
+///
+/// Default backoff applied to a throttling / upstream-failure status when
+/// the caller does not provide an explicit [`ActionError::throttled`] hint.
+/// Returns `None` for statuses that a client should not blindly retry.
+///
+fn default_retry_after_ms(status: StatusCode) -> Option<u64> {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => Some(1_000),
+        StatusCode::SERVICE_UNAVAILABLE => Some(5_000),
+        StatusCode::GATEWAY_TIMEOUT => Some(2_000),
+        _ => None,
+    }
+}
 
 impl ActionError {
-    pub fn new(http_status_code: StatusCode, message: &'static str) -> Self {
+    pub fn new(http_status_code: StatusCode, message: impl Into<Cow<'static, str>>) -> Self {
         Self {
             number: http_status_code.as_u16(),
+            errno: status_errno(http_status_code),
             reason: http_status_code.canonical_reason().unwrap_or_default(),
-            message,
+            message: message.into(),
+            info: ERROR_INFO_URL,
+            retry_after_ms: default_retry_after_ms(http_status_code),
+            source: None,
         }
     }
+
+    ///
+    /// Build a throttling / upstream-failure error with an explicit backoff
+    /// hint. When `retry_after_ms` is `None` the per-status default from
+    /// [`default_retry_after_ms`] is used, so `throttled(TOO_MANY_REQUESTS,
+    /// None)` still yields a sensible bound.
+    ///
+    pub fn throttled(known: KnownError, retry_after_ms: Option<u64>) -> Self {
+        let status = known.0;
+        let mut error = ActionError::from(known);
+        error.retry_after_ms = retry_after_ms.or_else(|| default_retry_after_ms(status));
+        error
+    }
+
+    ///
+    /// Attach an underlying cause, surfaced through
+    /// [`std::error::Error::source`] and kept out of the serialized response.
+    ///
+    pub fn with_source(mut self, cause: impl Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Box::new(cause));
+        self
+    }
+
+    ///
+    /// Build an error from a `KnownError`, appending runtime `detail` to the
+    /// constant message so the response names the offending resource, e.g.
+    /// the invalid VSS path or the missing subscription id.
+    ///
+    pub fn with_context(known: KnownError, detail: impl fmt::Display) -> Self {
+        let mut error = ActionError::from(known);
+        error.message = Cow::Owned(format!("{} ({})", error.message.trim_end_matches('.'), detail));
+        error
+    }
 }
 
 impl From<io::Error> for ActionError {
@@ -51,10 +195,14 @@ impl From<io::Error> for ActionError {
         warn!("io::Error {:?}", error);
         Self {
             number: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+            errno: status_errno(StatusCode::INTERNAL_SERVER_ERROR),
             reason: StatusCode::INTERNAL_SERVER_ERROR
                 .canonical_reason()
                 .unwrap_or_default(),
-            message: "",
+            message: Cow::Owned(error.to_string()),
+            info: ERROR_INFO_URL,
+            retry_after_ms: None,
+            source: Some(Box::new(error)),
         }
     }
 }
@@ -63,13 +211,17 @@ impl From<StatusCode> for ActionError {
     fn from(status_code: StatusCode) -> Self {
         Self {
             number: status_code.as_u16(),
+            errno: status_errno(status_code),
             reason: status_code.canonical_reason().unwrap_or_default(),
-            message: "",
+            message: Cow::Borrowed(""),
+            info: ERROR_INFO_URL,
+            retry_after_ms: default_retry_after_ms(status_code),
+            source: None,
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
 #[serde(tag = "action")]
 #[serde(rename_all = "camelCase")]
 pub enum ActionErrorResponse {
@@ -164,10 +316,9 @@ pub enum ActionErrorResponse {
 }
 
 impl From<io::Error> for ActionErrorResponse {
-    fn from(_: io::Error) -> Self {
-        let action_error = ActionError::new(StatusCode::INTERNAL_SERVER_ERROR, "");
+    fn from(error: io::Error) -> Self {
         ActionErrorResponse::SubscriptionNotification {
-            error: action_error,
+            error: ActionError::from(error),
             timestamp: unix_timestamp_ms(),
             subscription_id: SubscriptionID::SubscriptionIDInt(0),
         }
@@ -180,28 +331,100 @@ impl fmt::Display for ActionErrorResponse {
     }
 }
 
-pub fn new_get_error(request_id: ReqID, error: ActionError) -> ActionErrorResponse {
-    ActionErrorResponse::Get {
-        request_id,
-        error,
-        timestamp: unix_timestamp_ms(),
+///
+/// The request action an error belongs to. Used by
+/// [`ActionErrorResponse::for_action`] to map a single error value onto the
+/// matching response variant, so new request types do not each need a
+/// hand-written constructor.
+///
+/// The `Unsubscribe` and `SubscriptionNotification` variants carry a
+/// `subscriptionId` and are built directly rather than through this enum.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    Authorize,
+    GetMetadata,
+    Get,
+    Set,
+    Subscribe,
+    Subscription,
+    UnsubscribeAll,
+}
+
+impl ActionErrorResponse {
+    ///
+    /// Wrap any error convertible into [`ActionError`] in the response
+    /// variant for `action`, letting handlers propagate failures with `?`.
+    ///
+    pub fn for_action(
+        action: Action,
+        request_id: ReqID,
+        err: impl Into<ActionError>,
+    ) -> ActionErrorResponse {
+        let error = err.into();
+        let timestamp = unix_timestamp_ms();
+        match action {
+            Action::Authorize => ActionErrorResponse::Authorize {
+                request_id,
+                error,
+                timestamp,
+            },
+            Action::GetMetadata => ActionErrorResponse::GetMetadata {
+                request_id,
+                error,
+                timestamp,
+            },
+            Action::Get => ActionErrorResponse::Get {
+                request_id,
+                error,
+                timestamp,
+            },
+            Action::Set => ActionErrorResponse::Set {
+                request_id,
+                error,
+                timestamp,
+            },
+            Action::Subscribe => ActionErrorResponse::Subscribe {
+                request_id,
+                error,
+                timestamp,
+            },
+            Action::Subscription => ActionErrorResponse::Subscription {
+                request_id,
+                error,
+                timestamp,
+            },
+            Action::UnsubscribeAll => ActionErrorResponse::UnsubscribeAll {
+                request_id,
+                error,
+                timestamp,
+            },
+        }
     }
 }
 
+pub fn new_get_error(request_id: ReqID, error: ActionError) -> ActionErrorResponse {
+    ActionErrorResponse::for_action(Action::Get, request_id, error)
+}
+
 pub fn new_set_error(request_id: ReqID, error: ActionError) -> ActionErrorResponse {
-    ActionErrorResponse::Set {
-        request_id,
-        error,
-        timestamp: unix_timestamp_ms(),
-    }
+    ActionErrorResponse::for_action(Action::Set, request_id, error)
 }
 
 pub fn new_subscribe_error(request_id: ReqID, error: ActionError) -> ActionErrorResponse {
-    ActionErrorResponse::Subscribe {
-        request_id,
-        error,
-        timestamp: unix_timestamp_ms(),
-    }
+    ActionErrorResponse::for_action(Action::Subscribe, request_id, error)
+}
+
+///
+/// Reject a subscribe request whose filter failed validation, reporting the
+/// concrete [`FilterError`] (unsupported operator, malformed bounds, …)
+/// rather than the single catch-all `filter_invalid`.
+///
+pub fn new_subscribe_filter_error(
+    request_id: ReqID,
+    error: FilterError,
+) -> ActionErrorResponse {
+    ActionErrorResponse::for_action(Action::Subscribe, request_id, error)
 }
 
 pub fn new_unsubscribe_error(
@@ -218,27 +441,15 @@ pub fn new_unsubscribe_error(
 }
 
 pub fn new_unsubscribe_all_error(request_id: ReqID, error: ActionError) -> ActionErrorResponse {
-    ActionErrorResponse::UnsubscribeAll {
-        request_id,
-        error,
-        timestamp: unix_timestamp_ms(),
-    }
+    ActionErrorResponse::for_action(Action::UnsubscribeAll, request_id, error)
 }
 
 pub fn new_get_metadata_error(request_id: ReqID, error: ActionError) -> ActionErrorResponse {
-    ActionErrorResponse::GetMetadata {
-        request_id,
-        error,
-        timestamp: unix_timestamp_ms(),
-    }
+    ActionErrorResponse::for_action(Action::GetMetadata, request_id, error)
 }
 
 pub fn new_authorize_error(request_id: ReqID, error: ActionError) -> ActionErrorResponse {
-    ActionErrorResponse::Authorize {
-        request_id,
-        error,
-        timestamp: unix_timestamp_ms(),
-    }
+    ActionErrorResponse::for_action(Action::Authorize, request_id, error)
 }
 
 pub fn new_deserialization_error() -> ActionError {
@@ -250,14 +461,21 @@ pub fn new_deserialization_error() -> ActionError {
 /// An error that is listed in the specification error table.
 /// [Error Doc](https://w3c.github.io/automotive/vehicle_data/vehicle_information_service.html#errors)
 ///
-pub struct KnownError(StatusCode, &'static str, &'static str);
+/// Fields are the HTTP status, the `reason`, the human readable `message`,
+/// and the stable `errno` that clients dispatch on.
+///
+pub struct KnownError(StatusCode, &'static str, &'static str, u32);
 
 impl From<KnownError> for ActionError {
     fn from(known_error: KnownError) -> Self {
         Self {
             number: known_error.0.as_u16(),
+            errno: known_error.3,
             reason: known_error.1,
-            message: known_error.2,
+            message: Cow::Borrowed(known_error.2),
+            info: ERROR_INFO_URL,
+            retry_after_ms: default_retry_after_ms(known_error.0),
+            source: None,
         }
     }
 }
@@ -266,120 +484,230 @@ pub const NOT_MODIFIED: KnownError = KnownError(
     StatusCode::NOT_MODIFIED,
     "not_modified",
     "No changes have been made by the server.",
+    30401,
 );
 
 pub const BAD_REQUEST: KnownError = KnownError(
     StatusCode::BAD_REQUEST,
     "bad_request",
     "The server is unable to fulfill the client request because the request is malformed.",
+    40001,
 );
 
 pub const BAD_REQUEST_FILTER_INVALID: KnownError = KnownError(
     StatusCode::BAD_REQUEST,
     "filter_invalid",
     "Filter requested on non-primitive type.",
+    40002,
+);
+
+pub const BAD_REQUEST_FILTER_UNSUPPORTED_OPERATOR: KnownError = KnownError(
+    StatusCode::BAD_REQUEST,
+    "filter_unsupported_operator",
+    "The filter uses an operator that is not supported.",
+    40003,
+);
+
+pub const BAD_REQUEST_FILTER_MALFORMED_BOUNDS: KnownError = KnownError(
+    StatusCode::BAD_REQUEST,
+    "filter_malformed_bounds",
+    "The filter interval or range bounds are malformed.",
+    40004,
+);
+
+pub const BAD_REQUEST_FILTER_NON_LEAF: KnownError = KnownError(
+    StatusCode::BAD_REQUEST,
+    "filter_non_leaf",
+    "A filter can only be applied to a leaf node.",
+    40005,
+);
+
+pub const BAD_REQUEST_FILTER_INTERVAL_OUT_OF_RANGE: KnownError = KnownError(
+    StatusCode::BAD_REQUEST,
+    "filter_interval_out_of_range",
+    "The requested sampling interval is out of range.",
+    40006,
+);
+
+pub const BAD_REQUEST_FILTER_UNKNOWN_KEY: KnownError = KnownError(
+    StatusCode::BAD_REQUEST,
+    "filter_unknown_key",
+    "The filter contains an unknown key.",
+    40007,
 );
 
 pub const UNAUTHORIZED_USER_TOKEN_EXPIRED: KnownError = KnownError(
     StatusCode::UNAUTHORIZED,
     "user_token_expired",
     "User token has expired.",
+    40101,
 );
 
 pub const UNAUTHORIZED_USER_TOKEN_INVALID: KnownError = KnownError(
     StatusCode::UNAUTHORIZED,
     "user_token_invalid",
     "User token is invalid.",
+    40102,
 );
 
 pub const UNAUTHORIZED_USER_TOKEN_MISSING: KnownError = KnownError(
     StatusCode::UNAUTHORIZED,
     "user_token_missing",
     "User token is missing.",
+    40103,
 );
 
 pub const UNAUTHORIZED_DEVICE_TOKEN_EXPIRED: KnownError = KnownError(
     StatusCode::UNAUTHORIZED,
     "device_token_expired",
     "Device token has expired.",
+    40104,
 );
 
 pub const UNAUTHORIZED_DEVICE_TOKEN_INVALID: KnownError = KnownError(
     StatusCode::UNAUTHORIZED,
     "device_token_invalid",
     "Device token is invalid.",
+    40105,
 );
 
 pub const UNAUTHORIZED_DEVICE_TOKEN_MISSING: KnownError = KnownError(
     StatusCode::UNAUTHORIZED,
     "device_token_missing",
     "Device token is missing.",
+    40106,
 );
 
 pub const UNAUTHORIZED_TOO_MANY_ATTEMPTS: KnownError = KnownError(
     StatusCode::UNAUTHORIZED,
     "too_many_attempts",
     "The client has failed to authenticate too many times.",
+    40107,
 );
 
 pub const UNAUTHORIZED_READ_ONLY: KnownError = KnownError(
     StatusCode::UNAUTHORIZED,
     "read_only",
     "The desired signal cannot be set since it is a read only signal.",
+    40108,
 );
 
 pub const FORBIDDEN_USER_FORBIDDEN: KnownError = KnownError(
     StatusCode::FORBIDDEN,
     "user_forbidden",
     "The user is not permitted to access the requested resource. Retrying does not help.",
+    40301,
 );
 
 pub const FORBIDDEN_USER_UNKNOWN: KnownError = KnownError(
     StatusCode::FORBIDDEN,
     "user_unknown",
     "The user is unknown. Retrying does not help.",
+    40302,
 );
 
 pub const FORBIDDEN_DEVICE_FORBIDDEN: KnownError = KnownError(
     StatusCode::FORBIDDEN,
     "device_forbidden",
     "The device is not permitted to access the requested resource. Retrying does not help.",
+    40303,
 );
 
 pub const FORBIDDEN_DEVICE_UNKNOWN: KnownError = KnownError(
     StatusCode::FORBIDDEN,
     "device_unknown",
     "The device is unknown. Retrying does not help.",
+    40304,
 );
 
 pub const NOT_FOUND_INVALID_PATH: KnownError = KnownError(
     StatusCode::NOT_FOUND,
     "invalid_path",
     "The specified data path does not exist.",
+    40401,
 );
 
-pub const NOT_FOUND_PRIVATE_PATH :KnownError = KnownError(StatusCode::NOT_FOUND, "private_path", "The specified data path is private and the request is not authorized to access signals on this path.");
+pub const NOT_FOUND_PRIVATE_PATH :KnownError = KnownError(StatusCode::NOT_FOUND, "private_path", "The specified data path is private and the request is not authorized to access signals on this path.", 40402);
 
 pub const NOT_FOUND_INVALID_SUBSCRIPTION_ID: KnownError = KnownError(
     StatusCode::NOT_FOUND,
     "invalid_subscriptionId",
     "The specified subscription was not found.",
+    40403,
 );
 
 pub const NOT_ACCEPTABLE: KnownError = KnownError(
     StatusCode::NOT_ACCEPTABLE,
     "not_acceptable",
     "The server is unable to generate content that is acceptable to the client",
+    40601,
 );
 
 pub const TOO_MANY_REQUESTS: KnownError = KnownError(
     StatusCode::TOO_MANY_REQUESTS,
     "too_many_requests",
     "The client has sent the server too many requests in a given amount of time.",
+    42901,
 );
 
-pub const BAD_GATEWAY :KnownError = KnownError(StatusCode::BAD_GATEWAY, "bad_gateway", "The server was acting as a gateway or proxy and received an invalid response from an upstream server.");
+pub const BAD_GATEWAY :KnownError = KnownError(StatusCode::BAD_GATEWAY, "bad_gateway", "The server was acting as a gateway or proxy and received an invalid response from an upstream server.", 50201);
 
-pub const SERVICE_UNAVAILABLE :KnownError = KnownError(StatusCode:: SERVICE_UNAVAILABLE, "service_unavailable", "The server is currently unable to handle the request due to a temporary overload or scheduled maintenance (which may be alleviated after some delay).");
+pub const SERVICE_UNAVAILABLE :KnownError = KnownError(StatusCode:: SERVICE_UNAVAILABLE, "service_unavailable", "The server is currently unable to handle the request due to a temporary overload or scheduled maintenance (which may be alleviated after some delay).", 50301);
 
-pub const GATEWAY_TIMEOUT :KnownError = KnownError(StatusCode::GATEWAY_TIMEOUT, "gateway_timeout", "The server did not receive a timely response from an upstream server it needed to access in order to complete the request.");
+pub const GATEWAY_TIMEOUT :KnownError = KnownError(StatusCode::GATEWAY_TIMEOUT, "gateway_timeout", "The server did not receive a timely response from an upstream server it needed to access in order to complete the request.", 50401);
+
+///
+/// The concrete failure modes encountered while validating a subscribe
+/// filter. Each variant names the offending filter clause and converts into
+/// the matching [`ActionError`], so the subscribe code path can report
+/// exactly why a filter was rejected instead of a single catch-all 400.
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum FilterError {
+    /// The filter requested an operator the server does not implement.
+    UnsupportedOperator(String),
+    /// The interval or range bounds could not be parsed or were inconsistent.
+    MalformedBounds(String),
+    /// A filter was requested on a branch node rather than a leaf signal.
+    NonLeafNode(String),
+    /// The requested sampling interval is outside the accepted range.
+    SamplingIntervalOutOfRange(u64),
+    /// The filter referenced a key that is not recognised.
+    UnknownKey(String),
+}
+
+impl FilterError {
+    /// The specification error this filter failure maps onto.
+    fn known_error(&self) -> KnownError {
+        match self {
+            FilterError::UnsupportedOperator(_) => BAD_REQUEST_FILTER_UNSUPPORTED_OPERATOR,
+            FilterError::MalformedBounds(_) => BAD_REQUEST_FILTER_MALFORMED_BOUNDS,
+            FilterError::NonLeafNode(_) => BAD_REQUEST_FILTER_NON_LEAF,
+            FilterError::SamplingIntervalOutOfRange(_) => BAD_REQUEST_FILTER_INTERVAL_OUT_OF_RANGE,
+            FilterError::UnknownKey(_) => BAD_REQUEST_FILTER_UNKNOWN_KEY,
+        }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterError::UnsupportedOperator(op) => write!(f, "unsupported operator '{}'", op),
+            FilterError::MalformedBounds(clause) => write!(f, "malformed bounds '{}'", clause),
+            FilterError::NonLeafNode(path) => write!(f, "'{}' is not a leaf node", path),
+            FilterError::SamplingIntervalOutOfRange(interval) => {
+                write!(f, "sampling interval {} is out of range", interval)
+            }
+            FilterError::UnknownKey(key) => write!(f, "unknown filter key '{}'", key),
+        }
+    }
+}
+
+impl Error for FilterError {}
+
+impl From<FilterError> for ActionError {
+    fn from(error: FilterError) -> Self {
+        let detail = error.to_string();
+        ActionError::with_context(error.known_error(), detail)
+    }
+}